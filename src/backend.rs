@@ -0,0 +1,285 @@
+//! Output backends that turn a `Signal` into an effect on the desktop.
+//!
+//! `run` only knows how to recognize gestures; it hands the resulting
+//! `Signal` to whichever `OutputBackend` was selected at startup so the same
+//! detector can drive niri directly or emulate a keyboard for anything else.
+
+use crate::Signal;
+
+pub trait OutputBackend: Send {
+    fn dispatch(&mut self, signal: Signal);
+}
+
+pub struct NiriBackend;
+
+impl NiriBackend {
+    pub fn connect() -> Self {
+        niri_ipc::socket::Socket::connect().expect("Failed to connect to niri");
+        NiriBackend
+    }
+}
+
+impl OutputBackend for NiriBackend {
+    fn dispatch(&mut self, signal: Signal) {
+        use niri_ipc::{Action, Request};
+        // `Socket::send` takes `self` by value (it's a one-shot
+        // request/reply socket), so there's nothing to hold onto between
+        // dispatches: connect fresh here, same as `connect()` above.
+        let socket = niri_ipc::socket::Socket::connect().expect("Failed to connect to niri");
+        match signal {
+            Signal::LeftColumn => {
+                println!("Left column");
+                let (_reply, _) = socket
+                    .send(Request::Action(Action::FocusColumnLeft {}))
+                    .unwrap();
+            }
+            Signal::RightColumn => {
+                println!("Right column");
+                let (_reply, _) = socket
+                    .send(Request::Action(Action::FocusColumnRight {}))
+                    .unwrap();
+            }
+            Signal::Up => {
+                println!("Up");
+                let (_reply, _) = socket
+                    .send(Request::Action(Action::FocusWindowOrWorkspaceUp {}))
+                    .unwrap();
+            }
+            Signal::Down => {
+                println!("Down");
+                let (_reply, _) = socket
+                    .send(Request::Action(Action::FocusWindowOrWorkspaceDown {}))
+                    .unwrap();
+            }
+            Signal::LeftMonitor => {
+                println!("Left screen");
+                let (_reply, _) = socket
+                    .send(Request::Action(Action::FocusMonitorLeft {}))
+                    .unwrap();
+            }
+            Signal::RightMonitor => {
+                println!("Right screen");
+                let (_reply, _) = socket
+                    .send(Request::Action(Action::FocusMonitorRight {}))
+                    .unwrap();
+            }
+            Signal::Nop => {}
+        }
+    }
+}
+
+mod uinput {
+    //! Minimal bindings for the pieces of the Linux `uinput` kernel API we
+    //! need, kept local to this module instead of pulling in a crate: just
+    //! enough ioctl plumbing to register a virtual keyboard and write
+    //! `input_event`s to it.
+
+    pub const EV_SYN: u16 = 0x00;
+    pub const EV_KEY: u16 = 0x01;
+    pub const SYN_REPORT: u16 = 0;
+
+    pub const KEY_UP: u16 = 103;
+    pub const KEY_LEFT: u16 = 105;
+    pub const KEY_RIGHT: u16 = 106;
+    pub const KEY_DOWN: u16 = 108;
+    pub const KEY_LEFTMETA: u16 = 125;
+
+    const UINPUT_IOCTL_BASE: u8 = b'U';
+    const UINPUT_MAX_NAME_SIZE: usize = 80;
+
+    const fn ioc(dir: u32, ty: u8, nr: u8, size: usize) -> libc::c_ulong {
+        const NRBITS: u32 = 8;
+        const TYPEBITS: u32 = 8;
+        const SIZEBITS: u32 = 14;
+        const NRSHIFT: u32 = 0;
+        const TYPESHIFT: u32 = NRSHIFT + NRBITS;
+        const SIZESHIFT: u32 = TYPESHIFT + TYPEBITS;
+        const DIRSHIFT: u32 = SIZESHIFT + SIZEBITS;
+        ((dir as libc::c_ulong) << DIRSHIFT)
+            | ((ty as libc::c_ulong) << TYPESHIFT)
+            | ((nr as libc::c_ulong) << NRSHIFT)
+            | ((size as libc::c_ulong) << SIZESHIFT)
+    }
+
+    const IOC_NONE: u32 = 0;
+    const IOC_WRITE: u32 = 1;
+
+    const fn io(nr: u8) -> libc::c_ulong {
+        ioc(IOC_NONE, UINPUT_IOCTL_BASE, nr, 0)
+    }
+
+    const fn iow<T>(nr: u8) -> libc::c_ulong {
+        ioc(IOC_WRITE, UINPUT_IOCTL_BASE, nr, std::mem::size_of::<T>())
+    }
+
+    pub const UI_SET_EVBIT: libc::c_ulong = iow::<libc::c_int>(100);
+    pub const UI_SET_KEYBIT: libc::c_ulong = iow::<libc::c_int>(101);
+    pub const UI_DEV_CREATE: libc::c_ulong = io(1);
+    pub const UI_DEV_DESTROY: libc::c_ulong = io(2);
+    pub const UI_DEV_SETUP: libc::c_ulong = iow::<UinputSetup>(3);
+
+    #[repr(C)]
+    pub struct InputId {
+        pub bustype: u16,
+        pub vendor: u16,
+        pub product: u16,
+        pub version: u16,
+    }
+
+    #[repr(C)]
+    pub struct UinputSetup {
+        pub id: InputId,
+        pub name: [u8; UINPUT_MAX_NAME_SIZE],
+        pub ff_effects_max: u32,
+    }
+
+    // Matches glibc's 64-bit `struct timeval` layout, which is what the
+    // kernel's `struct input_event` embeds on this target.
+    #[repr(C)]
+    pub struct InputEvent {
+        pub tv_sec: i64,
+        pub tv_usec: i64,
+        pub ev_type: u16,
+        pub code: u16,
+        pub value: i32,
+    }
+}
+
+/// Emulates a keyboard through `/dev/uinput` so `byhead` can drive any
+/// Wayland/X11 compositor, not just niri.
+pub struct UinputBackend {
+    fd: std::os::unix::io::RawFd,
+}
+
+impl UinputBackend {
+    pub fn create() -> Self {
+        let path = std::ffi::CString::new("/dev/uinput").unwrap();
+        let fd = unsafe { libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_NONBLOCK) };
+        assert!(fd >= 0, "Failed to open /dev/uinput");
+
+        let keys = [
+            uinput::KEY_UP,
+            uinput::KEY_LEFT,
+            uinput::KEY_RIGHT,
+            uinput::KEY_DOWN,
+            uinput::KEY_LEFTMETA,
+        ];
+        unsafe {
+            let rc = libc::ioctl(fd, uinput::UI_SET_EVBIT, uinput::EV_KEY as libc::c_int);
+            assert!(rc == 0, "UI_SET_EVBIT failed");
+            for &key in &keys {
+                let rc = libc::ioctl(fd, uinput::UI_SET_KEYBIT, key as libc::c_int);
+                assert!(rc == 0, "UI_SET_KEYBIT failed for key {key}");
+            }
+
+            let mut name = [0u8; 80];
+            name[..b"byhead".len()].copy_from_slice(b"byhead");
+            let setup = uinput::UinputSetup {
+                id: uinput::InputId {
+                    bustype: 0x03, // BUS_USB
+                    vendor: 0x1234,
+                    product: 0x5678,
+                    version: 1,
+                },
+                name,
+                ff_effects_max: 0,
+            };
+            let rc = libc::ioctl(
+                fd,
+                uinput::UI_DEV_SETUP,
+                &setup as *const uinput::UinputSetup,
+            );
+            assert!(rc == 0, "UI_DEV_SETUP failed");
+            let rc = libc::ioctl(fd, uinput::UI_DEV_CREATE);
+            assert!(rc == 0, "UI_DEV_CREATE failed");
+        }
+
+        UinputBackend { fd }
+    }
+
+    fn write_event(&self, ev_type: u16, code: u16, value: i32) {
+        let event = uinput::InputEvent {
+            tv_sec: 0,
+            tv_usec: 0,
+            ev_type,
+            code,
+            value,
+        };
+        let event_bytes = unsafe {
+            std::slice::from_raw_parts(
+                &event as *const uinput::InputEvent as *const u8,
+                std::mem::size_of::<uinput::InputEvent>(),
+            )
+        };
+        let written = unsafe {
+            libc::write(
+                self.fd,
+                event_bytes.as_ptr() as *const libc::c_void,
+                event_bytes.len(),
+            )
+        };
+        assert!(written == event_bytes.len() as isize, "Short uinput write");
+    }
+
+    fn press_and_release(&self, keys: &[u16]) {
+        for &key in keys {
+            self.write_event(uinput::EV_KEY, key, 1);
+        }
+        self.write_event(uinput::EV_SYN, uinput::SYN_REPORT, 0);
+        for &key in keys.iter().rev() {
+            self.write_event(uinput::EV_KEY, key, 0);
+        }
+        self.write_event(uinput::EV_SYN, uinput::SYN_REPORT, 0);
+    }
+}
+
+impl OutputBackend for UinputBackend {
+    fn dispatch(&mut self, signal: Signal) {
+        match signal {
+            Signal::LeftColumn => {
+                println!("Left column");
+                self.press_and_release(&[uinput::KEY_LEFT]);
+            }
+            Signal::RightColumn => {
+                println!("Right column");
+                self.press_and_release(&[uinput::KEY_RIGHT]);
+            }
+            Signal::Up => {
+                println!("Up");
+                self.press_and_release(&[uinput::KEY_UP]);
+            }
+            Signal::Down => {
+                println!("Down");
+                self.press_and_release(&[uinput::KEY_DOWN]);
+            }
+            Signal::LeftMonitor => {
+                println!("Left screen");
+                self.press_and_release(&[uinput::KEY_LEFTMETA, uinput::KEY_LEFT]);
+            }
+            Signal::RightMonitor => {
+                println!("Right screen");
+                self.press_and_release(&[uinput::KEY_LEFTMETA, uinput::KEY_RIGHT]);
+            }
+            Signal::Nop => {}
+        }
+    }
+}
+
+impl Drop for UinputBackend {
+    fn drop(&mut self) {
+        unsafe {
+            libc::ioctl(self.fd, uinput::UI_DEV_DESTROY);
+            libc::close(self.fd);
+        }
+    }
+}
+
+/// Picks the output backend from the `BACKEND` env var (`niri`, the
+/// default, or `uinput`), mirroring how `PORT` is read in `main`.
+pub fn from_env() -> Box<dyn OutputBackend> {
+    match std::env::var("BACKEND").as_deref() {
+        Ok("uinput") => Box::new(UinputBackend::create()),
+        Ok("niri") | Err(_) => Box::new(NiriBackend::connect()),
+        Ok(other) => panic!("Unknown BACKEND: {other}"),
+    }
+}
@@ -2,6 +2,9 @@ use std::convert::TryInto;
 use std::net::UdpSocket;
 use std::time::Instant;
 
+mod backend;
+mod replay;
+
 #[derive(Debug, Clone, Copy, PartialEq, Default)]
 struct Pose {
     x: f64,
@@ -111,6 +114,14 @@ impl PoseRecord {
     }
 }
 
+/// A validated opentrack frame paired with its offset (in seconds) from the
+/// start of the run, whether that's wall-clock time for a live UDP feed or a
+/// recorded offset being replayed. `process_frames` derives its notion of
+/// "now" from this offset instead of calling `Instant::now()` directly, so
+/// velocity/acceleration come out the same whether frames are paced in real
+/// time or played back as fast as possible (see `replay::Replayer::feed`).
+type RawFrame = ([f64; 6], f64);
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum Signal {
     LeftColumn,
@@ -122,6 +133,7 @@ enum Signal {
     Nop,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum State {
     LeftYawing {
         start: Instant,
@@ -131,197 +143,109 @@ enum State {
         start: Instant,
         end: Option<Instant>,
     },
+    PitchingUp {
+        start: Instant,
+        end: Option<Instant>,
+    },
+    PitchingDown {
+        start: Instant,
+        end: Option<Instant>,
+    },
     Idle,
 }
 
-fn run(port: u16) -> std::io::Result<()> {
+/// Tunables for the `State` machine: entry thresholds fire a gesture, exit
+/// thresholds (set lower, for hysteresis) release it, and `dwell_time` is how
+/// long velocity must stay under the exit threshold before we latch back to
+/// `Idle`, so jitter right at the boundary can't retrigger a signal.
+#[derive(Debug, Clone, Copy)]
+struct GestureConfig {
+    yaw_enter_threshold: f64,
+    yaw_exit_threshold: f64,
+    pitch_enter_threshold: f64,
+    pitch_exit_threshold: f64,
+    accel_threshold: f64,
+    idle_time: std::time::Duration,
+    dwell_time: std::time::Duration,
+}
+
+impl Default for GestureConfig {
+    fn default() -> Self {
+        GestureConfig {
+            yaw_enter_threshold: 36.0,
+            yaw_exit_threshold: 12.0,
+            pitch_enter_threshold: 40.0,
+            pitch_exit_threshold: 14.0,
+            accel_threshold: 1000.0,
+            idle_time: std::time::Duration::from_millis(500),
+            dwell_time: std::time::Duration::from_millis(120),
+        }
+    }
+}
+
+/// How often a held directional gesture re-dispatches its signal to the output
+/// backend while the head stays turned (hold-to-scroll through columns/workspaces).
+const REPEAT_INTERVAL: std::time::Duration = std::time::Duration::from_millis(200);
+
+/// How long the signal thread waits without hearing from the processing
+/// thread before it treats the gesture as released and resets to idle.
+const SIGNAL_IDLE_TIMEOUT: std::time::Duration = std::time::Duration::from_millis(500);
+
+fn run(port: u16, trace_file: Option<&str>) -> std::io::Result<()> {
     let socket = UdpSocket::bind(format!("0.0.0.0:{}", port))?;
     let mut buf = [0u8; 1024];
     let start = std::time::Instant::now();
-    let (raw_tx, raw_rx) = std::sync::mpsc::sync_channel::<[f64; 6]>(1);
-    let (sig_tx, sig_rx) = std::sync::mpsc::sync_channel::<Signal>(1);
+    let mut recorder = trace_file.map(replay::Recorder::create).transpose()?;
+    let (raw_tx, raw_rx) = crossbeam_channel::bounded::<RawFrame>(1);
+    let (sig_tx, sig_rx) = crossbeam_channel::bounded::<Signal>(1);
     let _sig_thread = std::thread::spawn(move || {
-        use niri_ipc::{Action, Request};
-        let mut prev_instant = std::time::Instant::now();
-        loop {
-            let socket = niri_ipc::socket::Socket::connect().expect("Failed to connect to niri");
-            let signal = sig_rx.recv().unwrap();
-            let now = std::time::Instant::now();
-            let delta = now.duration_since(prev_instant).as_secs_f64();
-            if delta < 0.20 {
-                prev_instant = now;
-                continue;
-            }
-            match signal {
-                Signal::LeftColumn => {
-                    println!("Left column");
-                    let (_reply, _) = socket
-                        .send(Request::Action(Action::FocusColumnLeft {}))
-                        .unwrap();
-                }
-                Signal::RightColumn => {
-                    println!("Right column");
-                    let (_reply, _) = socket
-                        .send(Request::Action(Action::FocusColumnRight {}))
-                        .unwrap();
-                }
-                Signal::Up => {
-                    println!("Up");
-                    let (_reply, _) = socket
-                        .send(Request::Action(Action::FocusWindowOrWorkspaceUp {}))
-                        .unwrap();
-                }
-                Signal::Down => {
-                    println!("Down");
-                    let (_reply, _) = socket
-                        .send(Request::Action(Action::FocusWindowOrWorkspaceDown {}))
-                        .unwrap();
-                }
-                Signal::LeftMonitor => {
-                    println!("Left screen");
-                    let (_reply, _) = socket
-                        .send(Request::Action(Action::FocusMonitorLeft {}))
-                        .unwrap();
-                }
-                Signal::RightMonitor => {
-                    println!("Right screen");
-                    let (_reply, _) = socket
-                        .send(Request::Action(Action::FocusMonitorRight {}))
-                        .unwrap();
-                }
-                _ => {}
-            }
-            prev_instant = now;
-        }
-    });
+        let mut backend = backend::from_env();
+        let ticker = crossbeam_channel::tick(REPEAT_INTERVAL);
+        let mut held: Option<Signal> = None;
+        let mut last_seen = std::time::Instant::now();
+        let mut last_dispatch = std::time::Instant::now() - REPEAT_INTERVAL;
 
-    std::thread::spawn(move || {
-        let mut history = std::collections::VecDeque::<PoseRecord>::with_capacity(4096);
         loop {
-            history.truncate(4000);
-            let now = std::time::Instant::now();
-            let [x, y, z, yaw, pitch, roll] = raw_rx.recv().expect("Failed to receive raw data");
-            let pose = Pose {
-                x,
-                y,
-                z,
-                yaw,
-                pitch,
-                roll,
-            };
-
-            let elapsed = now.duration_since(start).as_secs_f64();
-            let Some(&prev) = history.front() else {
-                history.push_front(PoseRecord {
-                    pose,
-                    v: Pose::default(),
-                    instant: now,
-                    delta: 0.0,
-                });
-                continue;
-            };
-            let delta = now.duration_since(prev.instant).as_secs_f64();
-
-            let record = if let Some(prev_2) = history.get(2) {
-                let v = pose.diff(
-                    prev_2.pose,
-                    now.duration_since(prev_2.instant).as_secs_f64(),
-                );
-                PoseRecord {
-                    pose,
-                    v,
-                    instant: now,
-                    delta,
+            crossbeam_channel::select! {
+                recv(sig_rx) -> msg => {
+                    let Ok(signal) = msg else { break };
+                    last_seen = std::time::Instant::now();
+                    if signal == Signal::Nop {
+                        held = None;
+                        continue;
+                    }
+                    held = Some(signal);
+                    // process_frames resends the held signal on every latched
+                    // frame whose velocity is above the exit threshold, so
+                    // dispatch is throttled here the same way the ticker
+                    // throttles repeats, instead of firing on every message.
+                    let now = std::time::Instant::now();
+                    if now.duration_since(last_dispatch) >= REPEAT_INTERVAL {
+                        backend.dispatch(signal);
+                        last_dispatch = now;
+                    }
                 }
-            } else {
-                let v = pose.diff(prev.pose, delta);
-                PoseRecord {
-                    pose,
-                    v,
-                    instant: now,
-                    delta,
-                }
-            };
-            history.push_front(record);
-            if delta > 1.0 {
-                println!("Delta too large: {}", delta);
-                history.truncate(1);
-                continue;
-            }
-            let history_len = history.len();
-            if history_len < 16 {
-                continue;
-            }
-            let yaw_threshold = 36.0;
-            let pitch_threshold = 40.0;
-            let idle_time = 500;
-            let accel_threshold = 1000.0;
-            let log_all = true;
-
-            let from_idle = history
-                .iter()
-                .skip(1)
-                .take_while(|x| now.duration_since(x.instant).as_millis() < idle_time)
-                .all(|x| {
-                    let v_yaw = x.v.yaw;
-                    let v_pitch = x.v.pitch;
-                    let same_direction = x.v.yaw.signum() == record.v.yaw.signum()
-                        && v_pitch.signum() == record.v.pitch.signum();
-                    (v_yaw.abs() < yaw_threshold && v_pitch.abs() < pitch_threshold)
-                        || same_direction
-                });
-
-            let acc = history[0].v.diff(history[1].v, delta);
-            if log_all && (elapsed.fract() * 100.0).floor() as i32 % 10 == 0 {
-                let arrow = record.pose.pitch_arrow();
-                let pitch = record.pose.pitch;
-                let v_pitch = record.v.pitch;
-                let acc_pitch = acc.pitch;
-                println!(
-                    "[{elapsed:10.3}] {arrow} pitch={pitch:8.4} v_pitch={v_pitch:8.4} acc_patch={acc_pitch:12.4}",
-                );
-            }
-            if record.v.yaw.abs() >= yaw_threshold
-                && acc.yaw.abs() > accel_threshold
-                && record.v.yaw.abs() > record.v.pitch.abs()
-            {
-                if !from_idle {
-                    println!("[YAW] NOT IDLE");
-                } else {
-                    let acc_yaw = acc.yaw;
-                    let v_yaw = record.v.yaw;
-                    let arrow = record.v.yaw_arrow().to_string().repeat(8);
-                    println!("{arrow} {v_yaw:12.4} acc_yaw={acc_yaw:12.4}",);
-                    sig_tx
-                        .send(if record.v.yaw < 0.0 {
-                            Signal::LeftColumn
-                        } else {
-                            Signal::RightColumn
-                        })
-                        .unwrap();
-                }
-            } else if record.v.pitch.abs() > pitch_threshold && acc.pitch.abs() > accel_threshold {
-                if !from_idle {
-                    println!("[PITCH] NOT IDLE");
-                } else {
-                    println!(
-                        "{} {from_idle}",
-                        record.v.pitch_arrow().to_string().repeat(8),
-                    );
-                    sig_tx
-                        .send(if record.v.pitch < 0.0 {
-                            Signal::Down
-                        } else {
-                            Signal::Up
-                        })
-                        .unwrap();
+                recv(ticker) -> _ => {
+                    let now = std::time::Instant::now();
+                    if now.duration_since(last_seen) >= SIGNAL_IDLE_TIMEOUT {
+                        held = None;
+                        continue;
+                    }
+                    if let Some(signal) = held {
+                        if now.duration_since(last_dispatch) >= REPEAT_INTERVAL {
+                            backend.dispatch(signal);
+                            last_dispatch = now;
+                        }
+                    }
                 }
             }
         }
     });
 
+    std::thread::spawn(move || process_frames(raw_rx, sig_tx, start));
+
     'recv: loop {
-        use std::sync::mpsc::TrySendError;
+        use crossbeam_channel::TrySendError;
         let (number_of_bytes, _src) = socket.recv_from(&mut buf)?;
         if number_of_bytes != 48 {
             println!("Received {} bytes, expected 48", number_of_bytes);
@@ -340,7 +264,13 @@ fn run(port: u16) -> std::io::Result<()> {
             println!("Received NaN");
             continue 'recv;
         }
-        match raw_tx.try_send(numbers) {
+        let offset = start.elapsed().as_secs_f64();
+        if let Some(recorder) = &mut recorder {
+            recorder
+                .record(&numbers, offset)
+                .expect("Failed to write trace frame");
+        }
+        match raw_tx.try_send((numbers, offset)) {
             Ok(_) => {}
             Err(TrySendError::Full(_)) => {
                 println!("Dropped a frame");
@@ -353,10 +283,237 @@ fn run(port: u16) -> std::io::Result<()> {
     }
 }
 
-fn main() -> std::io::Result<()> {
-    let port = std::env::var("PORT")
+/// Consumes validated `RawFrame`s from `raw_rx`, runs them through the
+/// yaw/pitch gesture recognizer, and sends the resulting `Signal`s to
+/// `sig_tx`. Split out of `run` so the replay path in `replay.rs` can drive
+/// it from a recorded trace instead of a live UDP feed (see `RawFrame`).
+fn process_frames(
+    raw_rx: crossbeam_channel::Receiver<RawFrame>,
+    sig_tx: crossbeam_channel::Sender<Signal>,
+    start: std::time::Instant,
+) {
+    let mut history = std::collections::VecDeque::<PoseRecord>::with_capacity(4096);
+    let config = GestureConfig::default();
+    let mut state = State::Idle;
+    loop {
+        history.truncate(4000);
+        let (frame, offset) = crossbeam_channel::select! {
+            recv(raw_rx) -> msg => match msg {
+                Ok(frame) => frame,
+                Err(_) => return,
+            },
+            default(config.idle_time) => {
+                // The pose stream stalled while a gesture was latched: force
+                // a reset instead of waiting forever for a frame that would
+                // otherwise re-evaluate the exit/dwell condition.
+                if !matches!(state, State::Idle) {
+                    println!("[IDLE] Stream stalled, resetting gesture state");
+                    state = State::Idle;
+                    sig_tx.send(Signal::Nop).ok();
+                }
+                continue;
+            }
+        };
+        let now = start + std::time::Duration::from_secs_f64(offset);
+        let [x, y, z, yaw, pitch, roll] = frame;
+        let pose = Pose {
+            x,
+            y,
+            z,
+            yaw,
+            pitch,
+            roll,
+        };
+
+        let elapsed = now.duration_since(start).as_secs_f64();
+        let Some(&prev) = history.front() else {
+            history.push_front(PoseRecord {
+                pose,
+                v: Pose::default(),
+                instant: now,
+                delta: 0.0,
+            });
+            continue;
+        };
+        let delta = now.duration_since(prev.instant).as_secs_f64();
+
+        let record = if let Some(prev_2) = history.get(2) {
+            let v = pose.diff(
+                prev_2.pose,
+                now.duration_since(prev_2.instant).as_secs_f64(),
+            );
+            PoseRecord {
+                pose,
+                v,
+                instant: now,
+                delta,
+            }
+        } else {
+            let v = pose.diff(prev.pose, delta);
+            PoseRecord {
+                pose,
+                v,
+                instant: now,
+                delta,
+            }
+        };
+        history.push_front(record);
+        if delta > 1.0 {
+            println!("Delta too large: {}", delta);
+            history.truncate(1);
+            continue;
+        }
+        let history_len = history.len();
+        if history_len < 16 {
+            continue;
+        }
+        let log_all = true;
+
+        let from_idle = history
+            .iter()
+            .skip(1)
+            .take_while(|x| now.duration_since(x.instant) < config.idle_time)
+            .all(|x| {
+                let v_yaw = x.v.yaw;
+                let v_pitch = x.v.pitch;
+                let same_direction = x.v.yaw.signum() == record.v.yaw.signum()
+                    && v_pitch.signum() == record.v.pitch.signum();
+                (v_yaw.abs() < config.yaw_enter_threshold
+                    && v_pitch.abs() < config.pitch_enter_threshold)
+                    || same_direction
+            });
+
+        let acc = history[0].v.diff(history[1].v, delta);
+        if log_all && (elapsed.fract() * 100.0).floor() as i32 % 10 == 0 {
+            let arrow = record.pose.pitch_arrow();
+            let pitch = record.pose.pitch;
+            let v_pitch = record.v.pitch;
+            let acc_pitch = acc.pitch;
+            println!(
+                    "[{elapsed:10.3}] {arrow} pitch={pitch:8.4} v_pitch={v_pitch:8.4} acc_patch={acc_pitch:12.4}",
+                );
+        }
+
+        match &mut state {
+            State::Idle => {
+                if record.v.yaw.abs() >= config.yaw_enter_threshold
+                    && acc.yaw.abs() > config.accel_threshold
+                    && record.v.yaw.abs() > record.v.pitch.abs()
+                {
+                    if !from_idle {
+                        println!("[YAW] NOT IDLE");
+                    } else {
+                        let acc_yaw = acc.yaw;
+                        let v_yaw = record.v.yaw;
+                        let arrow = record.v.yaw_arrow().to_string().repeat(8);
+                        println!("{arrow} {v_yaw:12.4} acc_yaw={acc_yaw:12.4}",);
+                        let signal = if record.v.yaw < 0.0 {
+                            state = State::LeftYawing {
+                                start: now,
+                                end: None,
+                            };
+                            Signal::LeftColumn
+                        } else {
+                            state = State::RightYawing {
+                                start: now,
+                                end: None,
+                            };
+                            Signal::RightColumn
+                        };
+                        sig_tx.send(signal).unwrap();
+                    }
+                } else if record.v.pitch.abs() > config.pitch_enter_threshold
+                    && acc.pitch.abs() > config.accel_threshold
+                {
+                    if !from_idle {
+                        println!("[PITCH] NOT IDLE");
+                    } else {
+                        println!(
+                            "{} {from_idle}",
+                            record.v.pitch_arrow().to_string().repeat(8),
+                        );
+                        let signal = if record.v.pitch < 0.0 {
+                            state = State::PitchingDown {
+                                start: now,
+                                end: None,
+                            };
+                            Signal::Down
+                        } else {
+                            state = State::PitchingUp {
+                                start: now,
+                                end: None,
+                            };
+                            Signal::Up
+                        };
+                        sig_tx.send(signal).unwrap();
+                    }
+                }
+            }
+            State::LeftYawing { end, .. } | State::RightYawing { end, .. } => {
+                if record.v.yaw.abs() <= config.yaw_exit_threshold {
+                    match *end {
+                        Some(since) if now.duration_since(since) >= config.dwell_time => {
+                            state = State::Idle;
+                        }
+                        Some(_) => {}
+                        None => *end = Some(now),
+                    }
+                } else {
+                    // Still actively turned: re-notify the signal thread so
+                    // its hold-to-repeat timeout doesn't expire out from
+                    // under a gesture the head is still holding.
+                    *end = None;
+                    let signal = if record.v.yaw < 0.0 {
+                        Signal::LeftColumn
+                    } else {
+                        Signal::RightColumn
+                    };
+                    sig_tx.send(signal).unwrap();
+                }
+            }
+            State::PitchingUp { end, .. } | State::PitchingDown { end, .. } => {
+                if record.v.pitch.abs() <= config.pitch_exit_threshold {
+                    match *end {
+                        Some(since) if now.duration_since(since) >= config.dwell_time => {
+                            state = State::Idle;
+                        }
+                        Some(_) => {}
+                        None => *end = Some(now),
+                    }
+                } else {
+                    *end = None;
+                    let signal = if record.v.pitch < 0.0 {
+                        Signal::Down
+                    } else {
+                        Signal::Up
+                    };
+                    sig_tx.send(signal).unwrap();
+                }
+            }
+        }
+    }
+}
+
+fn read_port() -> u16 {
+    std::env::var("PORT")
         .expect("PORT is not set")
         .parse()
-        .expect("Failed to parse PORT");
-    run(port)
+        .expect("Failed to parse PORT")
+}
+
+fn main() -> std::io::Result<()> {
+    let mode = std::env::var("MODE").unwrap_or_else(|_| "live".to_string());
+    match mode.as_str() {
+        "live" => run(read_port(), None),
+        "record" => {
+            let trace_file = std::env::var("TRACE_FILE").expect("TRACE_FILE is not set");
+            run(read_port(), Some(&trace_file))
+        }
+        "replay" => {
+            let trace_file = std::env::var("TRACE_FILE").expect("TRACE_FILE is not set");
+            let realtime = std::env::var("REALTIME").map_or(true, |v| v != "0");
+            replay::run_replay(&trace_file, realtime)
+        }
+        other => panic!("Unknown MODE: {other}"),
+    }
 }
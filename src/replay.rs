@@ -0,0 +1,264 @@
+//! Record/replay for the raw `RawFrame` opentrack frames that feed the
+//! processing thread, so gesture-detection changes can be regression-tested
+//! against a recorded motion without a live opentrack feed and a head.
+//!
+//! The trace format mirrors the UDP packet layout already parsed in `run`:
+//! each record is an 8-byte little-endian `f64` time offset (seconds since
+//! the start of the recording) followed by the 6 little-endian `f64`s of
+//! the frame itself.
+
+use crate::{RawFrame, Signal};
+use std::io::Write;
+
+const RECORD_SIZE: usize = 8 * 7;
+
+pub struct Recorder {
+    file: std::fs::File,
+}
+
+impl Recorder {
+    pub fn create(path: &str) -> std::io::Result<Self> {
+        let file = std::fs::File::create(path)?;
+        Ok(Recorder { file })
+    }
+
+    pub fn record(&mut self, frame: &[f64; 6], offset: f64) -> std::io::Result<()> {
+        self.file.write_all(&offset.to_le_bytes())?;
+        for &value in frame {
+            self.file.write_all(&value.to_le_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+pub struct Replayer {
+    frames: Vec<(f64, [f64; 6])>,
+}
+
+impl Replayer {
+    pub fn load(path: &str) -> std::io::Result<Self> {
+        let bytes = std::fs::read(path)?;
+        let mut frames = Vec::with_capacity(bytes.len() / RECORD_SIZE);
+        for record in bytes.chunks_exact(RECORD_SIZE) {
+            let offset = f64::from_le_bytes(record[0..8].try_into().unwrap());
+            let mut values = [0f64; 6];
+            for i in 0..6 {
+                let start = 8 + i * 8;
+                let end = start + 8;
+                values[i] = f64::from_le_bytes(record[start..end].try_into().unwrap());
+            }
+            frames.push((offset, values));
+        }
+        Ok(Replayer { frames })
+    }
+
+    /// Feeds every recorded frame into `raw_tx`, paired with its recorded
+    /// offset (see `RawFrame`). With `realtime` set, frames are additionally
+    /// paced with real sleeps to land at their original offset from the
+    /// start of the replay; otherwise they're sent back-to-back for fast
+    /// batch runs.
+    fn feed(&self, raw_tx: &crossbeam_channel::Sender<RawFrame>, realtime: bool) {
+        let replay_start = std::time::Instant::now();
+        for &(offset, values) in &self.frames {
+            if realtime {
+                let target = replay_start + std::time::Duration::from_secs_f64(offset);
+                let now = std::time::Instant::now();
+                if target > now {
+                    std::thread::sleep(target - now);
+                }
+            }
+            if raw_tx.send((values, offset)).is_err() {
+                break;
+            }
+        }
+    }
+}
+
+/// Drives `process_frames` from a recorded trace instead of a live UDP feed
+/// and collects every `Signal` it emits, in order.
+fn replay_signals(path: &str, realtime: bool) -> std::io::Result<Vec<Signal>> {
+    let replayer = Replayer::load(path)?;
+    let start = std::time::Instant::now();
+    let (raw_tx, raw_rx) = crossbeam_channel::bounded::<RawFrame>(1);
+    let (sig_tx, sig_rx) = crossbeam_channel::bounded::<Signal>(1);
+
+    let processing = std::thread::spawn(move || crate::process_frames(raw_rx, sig_tx, start));
+    let collector = std::thread::spawn(move || sig_rx.iter().collect::<Vec<_>>());
+
+    replayer.feed(&raw_tx, realtime);
+    drop(raw_tx);
+
+    processing.join().expect("Processing thread panicked");
+    Ok(collector.join().expect("Signal collector thread panicked"))
+}
+
+/// Runs the processing thread against a recorded trace instead of a live
+/// UDP feed, printing every `Signal` it emits. This is the golden-file path:
+/// record a known motion once with `MODE=record`, then replay it after a
+/// detector change and diff the printed signal sequence against what was
+/// recorded as "known good".
+pub fn run_replay(path: &str, realtime: bool) -> std::io::Result<()> {
+    for signal in replay_signals(path, realtime)? {
+        println!("{signal:?}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(yaw: f64, pitch: f64) -> [f64; 6] {
+        [0.0, 0.0, 0.0, yaw, pitch, 0.0]
+    }
+
+    fn temp_trace_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!(
+            "byhead-test-{:?}-{name}.trace",
+            std::thread::current().id()
+        ))
+    }
+
+    /// Builds a synthetic "look left" trace: pose held still, then yaw
+    /// sweeping hard to the left for long enough to clear both the enter
+    /// and acceleration thresholds, then held at the new yaw so the gesture
+    /// settles instead of immediately releasing.
+    fn write_look_left_trace(path: &str) {
+        let mut recorder = Recorder::create(path).unwrap();
+        for i in 0..20 {
+            recorder.record(&frame(0.0, 0.0), i as f64 * 0.02).unwrap();
+        }
+        let mut offset = 20.0 * 0.02;
+        let mut yaw = 0.0;
+        for _ in 0..20 {
+            yaw -= 3.0;
+            offset += 0.02;
+            recorder.record(&frame(yaw, 0.0), offset).unwrap();
+        }
+        for _ in 0..10 {
+            offset += 0.02;
+            recorder.record(&frame(yaw, 0.0), offset).unwrap();
+        }
+    }
+
+    #[test]
+    fn replay_of_look_left_emits_left_column() {
+        let path = temp_trace_path("look-left");
+        let path = path.to_str().unwrap();
+        write_look_left_trace(path);
+
+        let signals = replay_signals(path, false).expect("replay failed");
+        std::fs::remove_file(path).ok();
+
+        assert!(
+            signals.contains(&Signal::LeftColumn),
+            "expected a LeftColumn signal, got {signals:?}"
+        );
+    }
+
+    /// Builds a trace that triggers a left-yaw gesture, settles into a yaw
+    /// rate that stays above `yaw_exit_threshold` (so the resend path keeps
+    /// firing), then briefly dips below the exit threshold for less than
+    /// `dwell_time` before climbing back above it, and finally keeps going
+    /// for as long again as the settled stretch before it.
+    ///
+    /// Once back in `State::Idle`, firing again requires clearing
+    /// `yaw_enter_threshold` (36.0), which this trace never does after the
+    /// dip — so any `LeftColumn` signals seen after the dip can only come from
+    /// the resend path in `LeftYawing`, which only fires while still latched.
+    /// If the dwell hysteresis let a sub-dwell dip release the gesture early,
+    /// those later signals would never appear.
+    fn write_left_yaw_jitter_trace(path: &str) {
+        let mut recorder = Recorder::create(path).unwrap();
+        let mut offset = 0.0;
+        let mut yaw = 0.0;
+        for _ in 0..20 {
+            recorder.record(&frame(yaw, 0.0), offset).unwrap();
+            offset += 0.02;
+        }
+        for _ in 0..20 {
+            yaw -= 3.5;
+            recorder.record(&frame(yaw, 0.0), offset).unwrap();
+            offset += 0.02;
+        }
+        for _ in 0..15 {
+            yaw -= 0.3;
+            recorder.record(&frame(yaw, 0.0), offset).unwrap();
+            offset += 0.02;
+        }
+        for _ in 0..2 {
+            yaw -= 0.05;
+            recorder.record(&frame(yaw, 0.0), offset).unwrap();
+            offset += 0.02;
+        }
+        for _ in 0..15 {
+            yaw -= 0.3;
+            recorder.record(&frame(yaw, 0.0), offset).unwrap();
+            offset += 0.02;
+        }
+    }
+
+    #[test]
+    fn brief_dip_below_exit_threshold_does_not_release_the_gesture() {
+        let path = temp_trace_path("left-yaw-jitter");
+        let path = path.to_str().unwrap();
+        write_left_yaw_jitter_trace(path);
+
+        let signals = replay_signals(path, false).expect("replay failed");
+        std::fs::remove_file(path).ok();
+
+        assert!(
+            signals.iter().all(|s| *s == Signal::LeftColumn),
+            "expected only LeftColumn signals, got {signals:?}"
+        );
+        let left_column_count = signals.iter().filter(|s| **s == Signal::LeftColumn).count();
+        // The onset, the rest of the sweep and the settled stretch emit 35
+        // signals before the dip; only resends after it push the count higher.
+        assert!(
+            left_column_count > 35,
+            "expected the gesture to keep resending after the dip, only saw \
+             {left_column_count} LeftColumn signals: {signals:?}"
+        );
+    }
+
+    /// Builds a synthetic "look up" trace, the pitch-axis counterpart of
+    /// `write_look_left_trace`.
+    fn write_look_up_trace(path: &str) {
+        let mut recorder = Recorder::create(path).unwrap();
+        for i in 0..20 {
+            recorder.record(&frame(0.0, 0.0), i as f64 * 0.02).unwrap();
+        }
+        let mut offset = 20.0 * 0.02;
+        let mut pitch = 0.0;
+        for _ in 0..20 {
+            pitch += 3.0;
+            offset += 0.02;
+            recorder.record(&frame(0.0, pitch), offset).unwrap();
+        }
+        for _ in 0..10 {
+            offset += 0.02;
+            recorder.record(&frame(0.0, pitch), offset).unwrap();
+        }
+    }
+
+    #[test]
+    fn replay_of_look_up_emits_up() {
+        let path = temp_trace_path("look-up");
+        let path = path.to_str().unwrap();
+        write_look_up_trace(path);
+
+        let signals = replay_signals(path, false).expect("replay failed");
+        std::fs::remove_file(path).ok();
+
+        assert!(
+            signals.contains(&Signal::Up),
+            "expected an Up signal, got {signals:?}"
+        );
+        assert!(
+            signals.iter().all(|s| *s != Signal::LeftColumn
+                && *s != Signal::RightColumn
+                && *s != Signal::Down),
+            "pitch-only motion should never emit a yaw or Down signal, got {signals:?}"
+        );
+    }
+}